@@ -6,9 +6,66 @@ use simperby_network::{
     NetworkConfig, Peer, SharedKnownPeers,
 };
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+mod mdns;
+mod peering;
+pub use peering::{ConnectionStatus, PeeringManager};
+
+/// A handle to a running [`Governance::serve`] task's peering mesh.
+#[derive(Clone)]
+pub struct MeshHandle(Arc<PeeringManager>);
+
+impl MeshHandle {
+    /// Returns a snapshot of every known peer's connection status.
+    pub async fn status(&self) -> HashMap<PublicKey, ConnectionStatus> {
+        self.0.mesh_status().await
+    }
+}
 
 pub type Error = anyhow::Error;
 const STATE_FILE_NAME: &str = "state.json";
+/// The number of events retained for a lagging subscriber before it starts missing them.
+const EVENT_CHANNEL_CAPACITY: usize = 128;
+const DEFAULT_RPC_PORT: u16 = 123;
+
+/// The transport over which [`Governance::serve`] exposes the governance RPC.
+#[derive(Clone, Debug)]
+pub enum TransportConfig {
+    /// A TCP port, reachable over the network.
+    Tcp(u16),
+    /// A Unix domain socket at the given filesystem path.
+    ///
+    /// Not yet wired up: [`Governance::serve`] returns an error for this variant until
+    /// `simperby_network` grows a non-TCP `DistributedMessageSet::serve`.
+    UnixSocket(std::path::PathBuf),
+    /// A Windows named pipe with the given name.
+    ///
+    /// Not yet wired up: [`Governance::serve`] returns an error for this variant until
+    /// `simperby_network` grows a non-TCP `DistributedMessageSet::serve`.
+    NamedPipe(String),
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        TransportConfig::Tcp(DEFAULT_RPC_PORT)
+    }
+}
+
+/// Resolves `transport` to the TCP port the underlying DMS should listen on.
+///
+/// `DistributedMessageSet::serve` only accepts a TCP port today; routing `UnixSocket`/
+/// `NamedPipe` through it needs a corresponding change in `simperby_network`, so those
+/// variants are rejected here rather than silently falling back to TCP.
+fn resolve_transport_port(transport: TransportConfig) -> Result<u16, Error> {
+    match transport {
+        TransportConfig::Tcp(port) => Ok(port),
+        TransportConfig::UnixSocket(_) | TransportConfig::NamedPipe(_) => Err(anyhow::anyhow!(
+            "transport {transport:?} is not yet supported by the underlying DMS serve()"
+        )),
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GovernanceState {
@@ -24,9 +81,107 @@ struct Vote {
     pub signature: Signature,
 }
 
+/// Deserializes `message` as a [`Vote`] and checks that its signature is valid and its
+/// voter belongs to `validator_set`.
+fn verify_vote(message: &Message, validator_set: &HashSet<PublicKey>) -> Result<Vote, Error> {
+    let vote: Vote = serde_json::from_str(message.data())
+        .map_err(|error| anyhow::anyhow!("failed to deserialize vote message: {error}"))?;
+    if !validator_set.contains(&vote.voter) {
+        return Err(anyhow::anyhow!("vote from a non-validator {:?}", vote.voter));
+    }
+    vote.signature
+        .verify(vote.agenda_hash, &vote.voter)
+        .map_err(|_| anyhow::anyhow!("vote with an invalid signature from {:?}", vote.voter))?;
+    Ok(vote)
+}
+
+/// Whether `vote_count` out of `validator_set_size` validators clears the quorum.
+fn has_quorum(vote_count: usize, validator_set_size: usize) -> bool {
+    vote_count * 3 > validator_set_size * 2
+}
+
+/// Returns the subset of `messages` not already accounted for in `applied_message_hashes`.
+fn filter_unapplied(messages: Vec<Message>, applied_message_hashes: &HashSet<Hash256>) -> Vec<Message> {
+    messages
+        .into_iter()
+        .filter(|message| !applied_message_hashes.contains(&Hash256::hash(message.data().as_bytes())))
+        .collect()
+}
+
+/// An event emitted by [`Governance`], delivered via [`Governance::subscribe`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum GovernanceEvent {
+    /// A vote on `agenda_hash` by `voter` was received.
+    VoteReceived {
+        agenda_hash: Hash256,
+        voter: PublicKey,
+    },
+    /// `agenda_hash` has collected enough votes to reach quorum.
+    QuorumReached { agenda_hash: Hash256 },
+    /// The governance state has advanced to `height`.
+    HeightAdvanced { height: BlockHeight },
+}
+
+/// Verifies `messages` against `validator_set` and folds the valid votes into `state`,
+/// emitting `VoteReceived`/`QuorumReached` events on `event_sender` and returning whether
+/// `state` changed.
+fn apply_votes(
+    state: &mut GovernanceState,
+    event_sender: &tokio::sync::broadcast::Sender<GovernanceEvent>,
+    applied_message_hashes: &mut HashSet<Hash256>,
+    quorum_reported: &mut HashSet<Hash256>,
+    messages: Vec<Message>,
+    validator_set: &HashSet<PublicKey>,
+) -> bool {
+    let mut changed = false;
+    for message in messages {
+        let message_hash = Hash256::hash(message.data().as_bytes());
+        let vote = match verify_vote(&message, validator_set) {
+            Ok(vote) => vote,
+            Err(error) => {
+                log::warn!("rejecting vote message: {error}");
+                continue;
+            }
+        };
+        applied_message_hashes.insert(message_hash);
+        let voters = state
+            .votes
+            .entry(vote.agenda_hash)
+            .or_insert_with(HashSet::new);
+        if !voters.insert(vote.voter.clone()) {
+            log::warn!(
+                "duplicate vote from {:?} for agenda {:?}, rejecting",
+                vote.voter,
+                vote.agenda_hash
+            );
+            continue;
+        }
+        changed = true;
+        let _ = event_sender.send(GovernanceEvent::VoteReceived {
+            agenda_hash: vote.agenda_hash,
+            voter: vote.voter,
+        });
+        if has_quorum(voters.len(), validator_set.len())
+            && quorum_reported.insert(vote.agenda_hash)
+        {
+            let _ = event_sender.send(GovernanceEvent::QuorumReached {
+                agenda_hash: vote.agenda_hash,
+            });
+        }
+    }
+    changed
+}
+
 pub struct Governance<N: GossipNetwork, S: Storage> {
     pub dms: DMS<N, S>,
     pub state: GovernanceState,
+    event_sender: tokio::sync::broadcast::Sender<GovernanceEvent>,
+    peering: Arc<PeeringManager>,
+    /// Hashes of the vote messages already verified at the current height (applied, or
+    /// rejected as a duplicate vote), so that [`Governance::fetch_delta`] skips them.
+    applied_message_hashes: HashSet<Hash256>,
+    /// Agendas for which `QuorumReached` has already been sent, so it fires exactly once.
+    quorum_reported: HashSet<Hash256>,
 }
 
 impl<N: GossipNetwork, S: Storage> Governance<N, S> {
@@ -53,23 +208,38 @@ impl<N: GossipNetwork, S: Storage> Governance<N, S> {
                 .read_file(STATE_FILE_NAME)
                 .await?,
         )?;
-        Ok(Self { dms, state })
+        let (event_sender, _) = tokio::sync::broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Ok(Self {
+            dms,
+            state,
+            event_sender,
+            peering: Arc::new(PeeringManager::new()),
+            applied_message_hashes: HashSet::new(),
+            quorum_reported: HashSet::new(),
+        })
     }
 
     pub async fn read(&self) -> Result<GovernanceState, Error> {
         Ok(self.state.clone())
     }
 
+    /// Subscribes to the real-time stream of [`GovernanceEvent`]s. Not retroactive.
+    pub fn subscribe(&self) -> impl Stream<Item = GovernanceEvent> {
+        BroadcastStream::new(self.event_sender.subscribe()).filter_map(|event| event.ok())
+    }
+
     pub async fn vote(
         &mut self,
         network_config: &NetworkConfig,
         known_peers: &[Peer],
         agenda_hash: Hash256,
         private_key: &PrivateKey,
+        validator_set: &HashSet<PublicKey>,
     ) -> Result<(), Error> {
+        let voter = private_key.public_key();
         let data = serde_json::to_string(&Vote {
             agenda_hash,
-            voter: private_key.public_key(),
+            voter,
             signature: Signature::sign(agenda_hash, private_key)?,
         })
         .unwrap();
@@ -79,8 +249,11 @@ impl<N: GossipNetwork, S: Storage> Governance<N, S> {
         )?;
 
         self.dms
-            .add_message(network_config, known_peers, message)
+            .add_message(network_config, known_peers, message.clone())
             .await?;
+        // Apply our own vote through the same verify-and-apply path as votes fetched from
+        // peers, so it's reported and counted toward quorum exactly once.
+        self.apply_messages(vec![message], validator_set).await?;
         Ok(())
     }
 
@@ -95,6 +268,12 @@ impl<N: GossipNetwork, S: Storage> Governance<N, S> {
             ));
         }
         self.dms.advance().await?;
+        self.applied_message_hashes.clear();
+        self.quorum_reported.clear();
+        let height: BlockHeight = self.dms.read_height().await?;
+        let _ = self
+            .event_sender
+            .send(GovernanceEvent::HeightAdvanced { height });
         Ok(())
     }
 
@@ -102,22 +281,278 @@ impl<N: GossipNetwork, S: Storage> Governance<N, S> {
         &mut self,
         network_config: &NetworkConfig,
         known_peers: &[Peer],
+        validator_set: &HashSet<PublicKey>,
+    ) -> Result<(), Error> {
+        let reachable_peers = self.reachable_peers(known_peers).await;
+        self.dms.fetch(network_config, &reachable_peers).await?;
+        self.update(validator_set).await?;
+        Ok(())
+    }
+
+    /// Like [`Governance::fetch`], but skips re-verifying messages already applied.
+    ///
+    /// TODO: `DistributedMessageSet` has no watermark/summary primitive, only a full
+    /// `fetch`, so this still pulls the whole message set over the network every call; a
+    /// corresponding `simperby_network` change is needed before peers can be asked for only
+    /// what's new. Until then this saves the redundant verification work, not the network
+    /// cost, by filtering out already-applied hashes locally before handing the rest to
+    /// [`Governance::apply_messages`].
+    pub async fn fetch_delta(
+        &mut self,
+        network_config: &NetworkConfig,
+        known_peers: &[Peer],
+        validator_set: &HashSet<PublicKey>,
     ) -> Result<(), Error> {
-        self.dms.fetch(network_config, known_peers).await?;
+        let reachable_peers = self.reachable_peers(known_peers).await;
+        self.dms.fetch(network_config, &reachable_peers).await?;
+        let delta = filter_unapplied(self.dms.read_messages().await?, &self.applied_message_hashes);
+        self.apply_messages(delta, validator_set).await?;
         Ok(())
     }
 
-    /// Serves the governance protocol indefinitely.
+    async fn reachable_peers(&self, known_peers: &[Peer]) -> Vec<Peer> {
+        let connected = self.peering.connected_peers().await;
+        if connected.is_empty() {
+            // No peer is currently marked up (the peering manager hasn't observed any peer
+            // yet, `serve()` was never called, or every observed peer is down); fall back to
+            // trying everyone rather than fetching from no one.
+            known_peers.to_vec()
+        } else {
+            known_peers
+                .iter()
+                .filter(|peer| {
+                    connected
+                        .iter()
+                        .any(|connected_peer| connected_peer.public_key == peer.public_key)
+                })
+                .cloned()
+                .collect()
+        }
+    }
+
+    /// Reads every DMS message for the current height and applies the valid votes among
+    /// them to `self.state.votes`.
+    async fn update(&mut self, validator_set: &HashSet<PublicKey>) -> Result<(), Error> {
+        let messages = self.dms.read_messages().await?;
+        self.apply_messages(messages, validator_set).await
+    }
+
+    /// Verifies and applies `messages` to `self.state.votes`, logging rather than panicking on an invalid one.
+    async fn apply_messages(
+        &mut self,
+        messages: Vec<Message>,
+        validator_set: &HashSet<PublicKey>,
+    ) -> Result<(), Error> {
+        let changed = apply_votes(
+            &mut self.state,
+            &self.event_sender,
+            &mut self.applied_message_hashes,
+            &mut self.quorum_reported,
+            messages,
+            validator_set,
+        );
+        if changed {
+            self.dms
+                .get_storage()
+                .write()
+                .await
+                .add_or_overwrite_file(STATE_FILE_NAME, serde_json::to_string(&self.state)?)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Serves the governance protocol indefinitely over `transport`, optionally discovering peers via mDNS.
     pub async fn serve(
         self,
         network_config: &NetworkConfig,
         peers: SharedKnownPeers,
-    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
-        const RPC_PORT: u16 = 123;
-        let join_handle = self
-            .dms
-            .serve(network_config.clone(), RPC_PORT, peers)
-            .await?;
-        Ok(join_handle)
+        transport: TransportConfig,
+        enable_mdns: bool,
+    ) -> Result<(tokio::task::JoinHandle<Result<(), Error>>, MeshHandle), Error>
+    where
+        DMS<N, S>: Clone,
+    {
+        let mesh_handle = MeshHandle(self.peering.clone());
+        let peering = self.peering.clone();
+        let peering_peers = peers.clone();
+        let peering_network_config = network_config.clone();
+        let peering_dms = self.dms.clone();
+        tokio::spawn(async move {
+            peering
+                .run(peering_peers, move |peer: Peer| {
+                    let mut dms = peering_dms.clone();
+                    let network_config = peering_network_config.clone();
+                    async move {
+                        dms.fetch(&network_config, std::slice::from_ref(&peer))
+                            .await
+                            .is_ok()
+                    }
+                })
+                .await;
+        });
+        if enable_mdns {
+            let mdns_network_config = network_config.clone();
+            let mdns_peers = peers.clone();
+            tokio::spawn(async move {
+                if let Err(error) = mdns::MdnsDiscovery::run(mdns_network_config, mdns_peers).await
+                {
+                    log::warn!("mDNS discovery stopped unexpectedly: {error}");
+                }
+            });
+        }
+        let port = resolve_transport_port(transport)?;
+        let join_handle = self.dms.serve(network_config.clone(), port, peers).await?;
+        Ok((join_handle, mesh_handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_keypair() -> (PrivateKey, PublicKey) {
+        let private_key = PrivateKey::generate();
+        let public_key = private_key.public_key();
+        (private_key, public_key)
+    }
+
+    fn test_vote_message(agenda_hash: Hash256, voter_key: &PrivateKey, network_key: &PrivateKey) -> Message {
+        let vote = Vote {
+            agenda_hash,
+            voter: voter_key.public_key(),
+            signature: Signature::sign(agenda_hash, voter_key).unwrap(),
+        };
+        let data = serde_json::to_string(&vote).unwrap();
+        Message::new(data.clone(), TypedSignature::sign(&data, network_key).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn has_quorum_requires_more_than_two_thirds() {
+        assert!(!has_quorum(2, 4));
+        assert!(has_quorum(3, 4));
+        assert!(has_quorum(4, 4));
+    }
+
+    #[test]
+    fn verify_vote_accepts_a_valid_validator_vote() {
+        let (voter_key, voter_public) = test_keypair();
+        let (network_key, _) = test_keypair();
+        let agenda_hash = Hash256::hash(b"agenda");
+        let message = test_vote_message(agenda_hash, &voter_key, &network_key);
+        let validator_set: HashSet<PublicKey> = [voter_public].into_iter().collect();
+        assert!(verify_vote(&message, &validator_set).is_ok());
+    }
+
+    #[test]
+    fn verify_vote_rejects_a_non_validator() {
+        let (voter_key, _) = test_keypair();
+        let (network_key, _) = test_keypair();
+        let (_, other_public) = test_keypair();
+        let agenda_hash = Hash256::hash(b"agenda");
+        let message = test_vote_message(agenda_hash, &voter_key, &network_key);
+        let validator_set: HashSet<PublicKey> = [other_public].into_iter().collect();
+        assert!(verify_vote(&message, &validator_set).is_err());
+    }
+
+    #[test]
+    fn verify_vote_rejects_an_invalid_signature() {
+        let (voter_key, voter_public) = test_keypair();
+        let (impostor_key, _) = test_keypair();
+        let (network_key, _) = test_keypair();
+        let agenda_hash = Hash256::hash(b"agenda");
+        let vote = Vote {
+            agenda_hash,
+            voter: voter_public.clone(),
+            // Signed by a different key than the one the vote claims to be from.
+            signature: Signature::sign(agenda_hash, &impostor_key).unwrap(),
+        };
+        let data = serde_json::to_string(&vote).unwrap();
+        let message =
+            Message::new(data.clone(), TypedSignature::sign(&data, &network_key).unwrap()).unwrap();
+        let validator_set: HashSet<PublicKey> = [voter_public].into_iter().collect();
+        assert!(verify_vote(&message, &validator_set).is_err());
+    }
+
+    #[test]
+    fn filter_unapplied_keeps_only_messages_not_already_applied() {
+        let (voter_key, _) = test_keypair();
+        let (network_key, _) = test_keypair();
+        let applied_message = test_vote_message(Hash256::hash(b"applied"), &voter_key, &network_key);
+        let new_message = test_vote_message(Hash256::hash(b"new"), &voter_key, &network_key);
+        let applied_message_hashes: HashSet<Hash256> =
+            [Hash256::hash(applied_message.data().as_bytes())]
+                .into_iter()
+                .collect();
+
+        let delta = filter_unapplied(
+            vec![applied_message, new_message.clone()],
+            &applied_message_hashes,
+        );
+
+        assert_eq!(delta.len(), 1);
+        assert_eq!(delta[0].data(), new_message.data());
+    }
+
+    #[test]
+    fn resolve_transport_port_accepts_tcp_and_rejects_the_rest() {
+        assert_eq!(resolve_transport_port(TransportConfig::Tcp(123)).unwrap(), 123);
+        assert!(resolve_transport_port(TransportConfig::UnixSocket("/tmp/governance.sock".into()))
+            .is_err());
+        assert!(resolve_transport_port(TransportConfig::NamedPipe("governance".to_string())).is_err());
+    }
+
+    #[tokio::test]
+    async fn apply_votes_reports_quorum_reached_only_once() {
+        let mut state = GovernanceState {
+            votes: HashMap::new(),
+            height: 0,
+        };
+        let (event_sender, receiver) = tokio::sync::broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let mut subscriber =
+            BroadcastStream::new(receiver).filter_map(|event| event.ok());
+        let mut applied_message_hashes = HashSet::new();
+        let mut quorum_reported = HashSet::new();
+
+        let agenda_hash = Hash256::hash(b"agenda");
+        let (network_key, _) = test_keypair();
+        let voters: Vec<(PrivateKey, PublicKey)> = (0..4).map(|_| test_keypair()).collect();
+        let validator_set: HashSet<PublicKey> =
+            voters.iter().map(|(_, public)| public.clone()).collect();
+
+        // The first 3 of 4 votes already cross quorum (3*3 > 4*2).
+        let first_batch: Vec<Message> = voters[..3]
+            .iter()
+            .map(|(private, _)| test_vote_message(agenda_hash, private, &network_key))
+            .collect();
+        apply_votes(
+            &mut state,
+            &event_sender,
+            &mut applied_message_hashes,
+            &mut quorum_reported,
+            first_batch,
+            &validator_set,
+        );
+
+        // The 4th vote crosses quorum again; QuorumReached must not fire a second time.
+        let second_batch = vec![test_vote_message(agenda_hash, &voters[3].0, &network_key)];
+        apply_votes(
+            &mut state,
+            &event_sender,
+            &mut applied_message_hashes,
+            &mut quorum_reported,
+            second_batch,
+            &validator_set,
+        );
+
+        let mut quorum_reached_count = 0;
+        while let Ok(Some(event)) =
+            tokio::time::timeout(std::time::Duration::from_millis(10), subscriber.next()).await
+        {
+            if matches!(event, GovernanceEvent::QuorumReached { .. }) {
+                quorum_reached_count += 1;
+            }
+        }
+        assert_eq!(quorum_reached_count, 1);
     }
 }
@@ -0,0 +1,55 @@
+use simperby_common::*;
+use simperby_network::{NetworkConfig, Peer, SharedKnownPeers};
+
+/// The mDNS service type under which Simperby nodes advertise themselves.
+const SERVICE_TYPE: &str = "_simperby._udp.local.";
+
+/// Advertises this node and discovers other Simperby nodes on the local network over mDNS, feeding them into [`SharedKnownPeers`].
+pub struct MdnsDiscovery;
+
+impl MdnsDiscovery {
+    /// Runs the advertise/discover loop indefinitely, inserting discovered peers into `known_peers`.
+    pub async fn run(
+        network_config: NetworkConfig,
+        known_peers: SharedKnownPeers,
+    ) -> Result<(), crate::Error> {
+        let public_key = network_config.private_key.public_key();
+        let daemon = mdns_sd::ServiceDaemon::new()?;
+        let service_info = mdns_sd::ServiceInfo::new(
+            SERVICE_TYPE,
+            &public_key.to_string(),
+            &format!("{public_key}.local."),
+            "",
+            network_config.port,
+            Some(
+                [("public_key", public_key.to_string())]
+                    .into_iter()
+                    .collect(),
+            ),
+        )?;
+        daemon.register(service_info)?;
+
+        let receiver = daemon.browse(SERVICE_TYPE)?;
+        while let Ok(event) = receiver.recv_async().await {
+            if let mdns_sd::ServiceEvent::ServiceResolved(info) = event {
+                if let Some(discovered_public_key) = info
+                    .get_property("public_key")
+                    .and_then(|property| property.val_str().parse::<PublicKey>().ok())
+                {
+                    if discovered_public_key == public_key {
+                        // That's just our own advertisement echoed back.
+                        continue;
+                    }
+                    let Some(address) = info.get_addresses().iter().next().copied() else {
+                        continue;
+                    };
+                    let endpoint = std::net::SocketAddr::new(address, info.get_port());
+                    known_peers
+                        .insert(Peer::new(discovered_public_key, endpoint))
+                        .await;
+                }
+            }
+        }
+        Ok(())
+    }
+}
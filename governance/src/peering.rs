@@ -0,0 +1,192 @@
+use simperby_common::*;
+use simperby_network::{Peer, SharedKnownPeers};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// How often the peering manager re-checks the liveness of every known peer.
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+/// The initial backoff delay before redialing a peer that just went down.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The maximum backoff delay between redial attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The liveness of a single peer, as observed by [`PeeringManager`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Up,
+    Down,
+}
+
+struct Connection {
+    peer: Peer,
+    status: ConnectionStatus,
+    backoff: Duration,
+    next_retry_at: tokio::time::Instant,
+}
+
+/// Doubles `backoff`, capped at `MAX_BACKOFF`.
+fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(MAX_BACKOFF)
+}
+
+/// Maintains a full-mesh connection table over [`SharedKnownPeers`] with automatic,
+/// backed-off reconnection.
+pub struct PeeringManager {
+    connections: RwLock<HashMap<PublicKey, Connection>>,
+}
+
+impl PeeringManager {
+    pub fn new() -> Self {
+        Self {
+            connections: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the peers currently believed to be reachable.
+    pub async fn connected_peers(&self) -> Vec<Peer> {
+        self.connections
+            .read()
+            .await
+            .values()
+            .filter(|connection| connection.status == ConnectionStatus::Up)
+            .map(|connection| connection.peer.clone())
+            .collect()
+    }
+
+    /// Returns a snapshot of every known peer's connection status, for operators to check
+    /// mesh health.
+    pub async fn mesh_status(&self) -> HashMap<PublicKey, ConnectionStatus> {
+        self.connections
+            .read()
+            .await
+            .iter()
+            .map(|(public_key, connection)| (*public_key, connection.status))
+            .collect()
+    }
+
+    /// Runs the probe/redial loop indefinitely, keeping the connection table in sync with
+    /// `known_peers`. `probe` reports whether `peer` is currently reachable; callers wire
+    /// this up to a real liveness signal, e.g. a `dms.fetch` round-trip against that peer.
+    pub async fn run<F, Fut>(&self, known_peers: SharedKnownPeers, probe: F)
+    where
+        F: Fn(Peer) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        loop {
+            let current_peers: Vec<Peer> = known_peers.read().await.iter().cloned().collect();
+            for peer in &current_peers {
+                self.refresh(peer, &probe).await;
+            }
+            self.prune(&current_peers).await;
+            tokio::time::sleep(PING_INTERVAL).await;
+        }
+    }
+
+    /// Drops connections for peers no longer present in `known_peers`, so a peer removed
+    /// from the known-peers list stops being reported as reachable.
+    async fn prune(&self, known_peers: &[Peer]) {
+        let known: HashSet<PublicKey> = known_peers.iter().map(|peer| peer.public_key).collect();
+        self.connections
+            .write()
+            .await
+            .retain(|public_key, _| known.contains(public_key));
+    }
+
+    async fn refresh<F, Fut>(&self, peer: &Peer, probe: &F)
+    where
+        F: Fn(Peer) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let now = tokio::time::Instant::now();
+        {
+            let connections = self.connections.read().await;
+            if let Some(connection) = connections.get(&peer.public_key) {
+                if connection.status == ConnectionStatus::Down && now < connection.next_retry_at {
+                    // Still backing off from the last failed redial attempt.
+                    return;
+                }
+            }
+        }
+
+        let reachable = probe(peer.clone()).await;
+        let mut connections = self.connections.write().await;
+        let connection = connections
+            .entry(peer.public_key)
+            .or_insert_with(|| Connection {
+                peer: peer.clone(),
+                status: ConnectionStatus::Down,
+                backoff: INITIAL_BACKOFF,
+                next_retry_at: now,
+            });
+        connection.peer = peer.clone();
+        if reachable {
+            connection.status = ConnectionStatus::Up;
+            connection.backoff = INITIAL_BACKOFF;
+        } else {
+            connection.status = ConnectionStatus::Down;
+            connection.next_retry_at = now + connection.backoff;
+            connection.backoff = next_backoff(connection.backoff);
+        }
+    }
+}
+
+impl Default for PeeringManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_backoff_doubles_and_caps_at_max() {
+        assert_eq!(next_backoff(INITIAL_BACKOFF), INITIAL_BACKOFF * 2);
+        assert_eq!(next_backoff(MAX_BACKOFF), MAX_BACKOFF);
+        assert_eq!(next_backoff(MAX_BACKOFF * 10), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn prune_drops_connections_for_peers_no_longer_known() {
+        let manager = PeeringManager::new();
+        let public_key = PrivateKey::generate().public_key();
+        let peer = Peer::new(public_key, "127.0.0.1:1234".parse().unwrap());
+        manager.connections.write().await.insert(
+            peer.public_key,
+            Connection {
+                peer: peer.clone(),
+                status: ConnectionStatus::Up,
+                backoff: INITIAL_BACKOFF,
+                next_retry_at: tokio::time::Instant::now(),
+            },
+        );
+
+        manager.prune(&[peer.clone()]).await;
+        assert_eq!(manager.connected_peers().await.len(), 1);
+
+        manager.prune(&[]).await;
+        assert!(manager.connected_peers().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn refresh_marks_a_peer_up_or_down_based_on_the_probe_result() {
+        let manager = PeeringManager::new();
+        let public_key = PrivateKey::generate().public_key();
+        let peer = Peer::new(public_key, "127.0.0.1:1234".parse().unwrap());
+
+        manager.refresh(&peer, &|_| async { true }).await;
+        assert_eq!(
+            manager.mesh_status().await.get(&peer.public_key),
+            Some(&ConnectionStatus::Up)
+        );
+
+        manager.refresh(&peer, &|_| async { false }).await;
+        assert_eq!(
+            manager.mesh_status().await.get(&peer.public_key),
+            Some(&ConnectionStatus::Down)
+        );
+    }
+}